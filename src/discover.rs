@@ -0,0 +1,244 @@
+//! Sends mDNS queries to discover devices and services on the LAN.
+
+use crate::{Error, Response};
+use async_std::net::UdpSocket;
+use async_std::task::{self, JoinHandle};
+use dns_parser::{Builder, QueryClass, QueryType};
+use futures_util::stream::{Stream, StreamExt};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// The multicast group mDNS queries and responses are exchanged on.
+pub(crate) const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub(crate) const MULTICAST_PORT: u16 = 5353;
+
+/// Selects how a [`Discovery`] transmits its queries.
+///
+/// Multicast is the default and works on any network where multicast traffic is allowed to
+/// flow. Unicast is useful on enterprise Wi-Fi and many cloud/VM networks, where multicast is
+/// filtered but regular unicast UDP still reaches every host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscoveryMode {
+    /// Send one query to the mDNS multicast group (224.0.0.251:5353).
+    Multicast,
+    /// Send a unicast query, with the "QU" (unicast response requested) bit set, to every host
+    /// address in `network`/`network_mask`.
+    Unicast {
+        network: Ipv4Addr,
+        network_mask: Ipv4Addr,
+    },
+}
+
+impl Default for DiscoveryMode {
+    fn default() -> Self {
+        DiscoveryMode::Multicast
+    }
+}
+
+/// A handle to an in-progress discovery. Call [`Discovery::listen`] to get a stream of
+/// responses.
+pub struct Discovery {
+    socket: UdpSocket,
+    service_names: Vec<String>,
+    timeout: Duration,
+    mode: DiscoveryMode,
+}
+
+/// Starts discovering devices/services advertising any of `service_names`, re-sending the query
+/// every `timeout`.
+pub fn all(service_names: Vec<String>, timeout: Duration) -> Result<Discovery, Error> {
+    all_with_mode(service_names, timeout, DiscoveryMode::Multicast)
+}
+
+/// Like [`all`], but lets the caller choose the [`DiscoveryMode`] queries are sent with.
+pub fn all_with_mode(
+    service_names: Vec<String>,
+    timeout: Duration,
+    mode: DiscoveryMode,
+) -> Result<Discovery, Error> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from(socket);
+
+    Ok(Discovery {
+        socket,
+        service_names,
+        timeout,
+        mode,
+    })
+}
+
+/// All host addresses in `network`/`network_mask`, excluding the network and broadcast
+/// addresses.
+pub(crate) fn unicast_hosts(
+    network: Ipv4Addr,
+    network_mask: Ipv4Addr,
+) -> impl Iterator<Item = Ipv4Addr> {
+    let network = u32::from(network) & u32::from(network_mask);
+    let broadcast = network | !u32::from(network_mask);
+
+    // `network + 1` overflows when `network` is `u32::MAX` (a /32 mask on 255.255.255.255). Saturate to `broadcast` instead so degenerate masks just yield an empty
+    // range rather than panicking (or, in release builds, wrapping around to a bogus range).
+    let first_host = network.checked_add(1).unwrap_or(broadcast);
+
+    (first_host..broadcast).map(Ipv4Addr::from)
+}
+
+fn build_query(service_name: &str, qu: bool) -> Result<Vec<u8>, Error> {
+    let mut builder = Builder::new_query(0, false);
+    builder.add_question(service_name, qu, QueryType::PTR, QueryClass::IN);
+    builder.build().map_err(|_| Error::ResponseError)
+}
+
+impl Discovery {
+    /// Sends the discovery query once, over the socket the configured [`DiscoveryMode`] calls
+    /// for.
+    async fn send_request(&self) -> Result<(), Error> {
+        for service_name in &self.service_names {
+            match self.mode {
+                DiscoveryMode::Multicast => {
+                    let packet = build_query(service_name, false)?;
+                    self.socket
+                        .send_to(&packet, (MULTICAST_ADDR, MULTICAST_PORT))
+                        .await?;
+                }
+                DiscoveryMode::Unicast {
+                    network,
+                    network_mask,
+                } => {
+                    let packet = build_query(service_name, true)?;
+                    for host in unicast_hosts(network, network_mask) {
+                        let dest = SocketAddr::V4(SocketAddrV4::new(host, MULTICAST_PORT));
+                        self.socket.send_to(&packet, dest).await?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turns this discovery into a stream of responses, re-querying every `timeout` until the
+    /// returned [`DiscoveryStream`] is dropped.
+    pub fn listen(self) -> DiscoveryStream {
+        let socket = self.socket.clone();
+
+        let query_task = task::spawn(async move {
+            loop {
+                if let Err(e) = self.send_request().await {
+                    log::warn!("mdns: failed to send discovery query: {}", e);
+                }
+
+                task::sleep(self.timeout).await;
+            }
+        });
+
+        DiscoveryStream {
+            inner: Box::pin(responses(socket)),
+            query_task: Some(query_task),
+        }
+    }
+}
+
+/// The stream returned by [`Discovery::listen`]. Dropping it cancels the task that re-sends the
+/// discovery query — without this, that task (and the socket it holds) would keep running, and
+/// querying the network, for the life of the process.
+pub struct DiscoveryStream {
+    inner: Pin<Box<dyn Stream<Item = Result<Response, Error>> + Send>>,
+    query_task: Option<JoinHandle<()>>,
+}
+
+impl Stream for DiscoveryStream {
+    type Item = Result<Response, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+impl Drop for DiscoveryStream {
+    fn drop(&mut self) {
+        // `JoinHandle` doesn't cancel its task on drop, only `.cancel().await` does — and
+        // `Drop::drop` can't await. Hand the cancellation off to its own detached task instead.
+        if let Some(query_task) = self.query_task.take() {
+            task::spawn(query_task.cancel());
+        }
+    }
+}
+
+/// Sends a single round of multicast queries for `service_names`, without listening for
+/// answers. Used by [`crate::resolve::browse`] to keep cached instances alive between the
+/// regular discovery interval.
+pub(crate) async fn requery(service_names: &[String]) -> Result<(), Error> {
+    let socket = UdpSocket::from(std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?);
+
+    for service_name in service_names {
+        let packet = build_query(service_name, false)?;
+        socket
+            .send_to(&packet, (MULTICAST_ADDR, MULTICAST_PORT))
+            .await?;
+    }
+
+    Ok(())
+}
+
+fn responses(socket: UdpSocket) -> impl Stream<Item = Result<Response, Error>> {
+    futures_util::stream::unfold(socket, |socket| async move {
+        let mut buffer = [0u8; 4096];
+
+        let result = match socket.recv_from(&mut buffer).await {
+            Ok((count, _)) => Response::from_packet(&buffer[..count]),
+            Err(e) => Err(e.into()),
+        };
+
+        Some((result, socket))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicast_hosts_excludes_network_and_broadcast() {
+        let hosts: Vec<_> =
+            unicast_hosts(Ipv4Addr::new(192, 168, 1, 0), Ipv4Addr::new(255, 255, 255, 0))
+                .collect();
+
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts.first(), Some(&Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(hosts.last(), Some(&Ipv4Addr::new(192, 168, 1, 254)));
+        assert!(!hosts.contains(&Ipv4Addr::new(192, 168, 1, 0)));
+        assert!(!hosts.contains(&Ipv4Addr::new(192, 168, 1, 255)));
+    }
+
+    #[test]
+    fn unicast_hosts_handles_a_slash_30() {
+        // A /30 has exactly two usable host addresses.
+        let hosts: Vec<_> =
+            unicast_hosts(Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(255, 255, 255, 252))
+                .collect();
+
+        assert_eq!(hosts, vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+    }
+
+    #[test]
+    fn unicast_hosts_handles_a_slash_32_without_overflowing() {
+        let hosts: Vec<_> =
+            unicast_hosts(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(255, 255, 255, 255)).collect();
+        assert!(hosts.is_empty());
+    }
+
+    #[test]
+    fn unicast_hosts_handles_the_highest_possible_address_without_overflowing() {
+        // network == u32::MAX is the degenerate case where `network + 1` would overflow.
+        let hosts: Vec<_> = unicast_hosts(
+            Ipv4Addr::new(255, 255, 255, 255),
+            Ipv4Addr::new(255, 255, 255, 255),
+        )
+        .collect();
+        assert!(hosts.is_empty());
+    }
+}
@@ -19,21 +19,47 @@
 //! }
 //! ```
 
-use crate::{Error, Response};
+use crate::discover::DiscoveryMode;
+use crate::{Error, RecordKind, Response};
+use async_std::stream::Stream;
+use async_std::sync::Mutex;
+use async_std::task::JoinHandle;
 use futures_util::{pin_mut, StreamExt, TryFutureExt};
-use std::time::Duration;
+use rand::Rng;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-/// Resolve a single device by hostname
+/// Resolve a single device by hostname, sending queries to the mDNS multicast group.
 pub async fn one<S>(
     service_names: Vec<String>,
     host_name: S,
     timeout: Duration,
 ) -> Result<Option<Response>, Error>
+where
+    S: AsRef<str>,
+{
+    one_with_mode(service_names, host_name, timeout, DiscoveryMode::Multicast).await
+}
+
+/// Resolve a single device by hostname, using the given [`DiscoveryMode`] to reach it. Pass
+/// [`DiscoveryMode::Unicast`] to probe a subnet directly when multicast traffic doesn't reach
+/// the device (common on enterprise Wi-Fi and cloud/VM networks).
+pub async fn one_with_mode<S>(
+    service_names: Vec<String>,
+    host_name: S,
+    timeout: Duration,
+    mode: DiscoveryMode,
+) -> Result<Option<Response>, Error>
 where
     S: AsRef<str>,
 {
     // by setting the query interval higher than the timeout we ensure we only make one query
-    let stream = crate::discover::all(service_names, timeout * 2)?.listen();
+    let stream = crate::discover::all_with_mode(service_names, timeout * 2, mode)?.listen();
     pin_mut!(stream);
 
     let process = async {
@@ -52,17 +78,31 @@ where
         .await
 }
 
-/// Resolve multiple devices by hostname
+/// Resolve multiple devices by hostname, sending queries to the mDNS multicast group.
 pub async fn multiple<S>(
     service_names: Vec<String>,
     host_names: &[S],
     timeout: Duration,
 ) -> Result<Vec<Response>, Error>
+where
+    S: AsRef<str>,
+{
+    multiple_with_mode(service_names, host_names, timeout, DiscoveryMode::Multicast).await
+}
+
+/// Resolve multiple devices by hostname, using the given [`DiscoveryMode`] to reach them. See
+/// [`one_with_mode`] for when to reach for [`DiscoveryMode::Unicast`].
+pub async fn multiple_with_mode<S>(
+    service_names: Vec<String>,
+    host_names: &[S],
+    timeout: Duration,
+    mode: DiscoveryMode,
+) -> Result<Vec<Response>, Error>
 where
     S: AsRef<str>,
 {
     // by setting the query interval higher than the timeout we ensure we only make one query
-    let stream = crate::discover::all(service_names, timeout * 2)?.listen();
+    let stream = crate::discover::all_with_mode(service_names, timeout * 2, mode)?.listen();
     pin_mut!(stream);
 
     let mut found = Vec::new();
@@ -87,3 +127,498 @@ where
         Err(e) => Err(e.into()),
     }
 }
+
+/// An instance joining or leaving the network, as reported by [`browse`].
+#[derive(Debug)]
+pub enum ServiceEvent {
+    /// A new instance was discovered.
+    Added(Response),
+    /// An instance left the network, either via a goodbye packet or because its TTL expired.
+    Removed(String),
+}
+
+/// Fractions of a record's original TTL at which [`browse`] re-queries to keep a live instance
+/// from expiring (RFC 6762 §5.2 recommends 80%, 85%, 90% and 95%).
+const KEEPALIVE_FRACTIONS: [f64; 4] = [0.80, 0.85, 0.90, 0.95];
+
+/// How often the keepalive task checks cached instances against their schedule.
+const KEEPALIVE_TICK: Duration = Duration::from_secs(1);
+
+struct CacheEntry {
+    ttl: Duration,
+    expires_at: Instant,
+    keepalives: Vec<Instant>,
+    next_keepalive: usize,
+}
+
+impl CacheEntry {
+    fn new(ttl: Duration) -> Self {
+        let now = Instant::now();
+        let jitter = || Duration::from_millis(rand::thread_rng().gen_range(0..250));
+        let keepalives = KEEPALIVE_FRACTIONS
+            .iter()
+            .map(|fraction| now + ttl.mul_f64(*fraction) + jitter())
+            .collect();
+
+        CacheEntry {
+            ttl,
+            expires_at: now + ttl,
+            keepalives,
+            next_keepalive: 0,
+        }
+    }
+
+    fn refresh(&mut self, ttl: Duration) {
+        *self = CacheEntry::new(ttl);
+    }
+}
+
+#[cfg(test)]
+mod cache_entry_tests {
+    use super::*;
+
+    #[test]
+    fn schedules_one_keepalive_per_fraction_before_expiry() {
+        let ttl = Duration::from_secs(100);
+        let before = Instant::now();
+        let entry = CacheEntry::new(ttl);
+
+        assert_eq!(entry.keepalives.len(), KEEPALIVE_FRACTIONS.len());
+        assert_eq!(entry.next_keepalive, 0);
+
+        // Keepalives fire in order, each before the TTL expires, and with only a little jitter
+        // past their nominal fraction of the TTL.
+        let mut previous = before;
+        for (fraction, keepalive) in KEEPALIVE_FRACTIONS.iter().zip(&entry.keepalives) {
+            assert!(*keepalive >= previous);
+            assert!(*keepalive < entry.expires_at);
+            assert!(*keepalive >= before + ttl.mul_f64(*fraction));
+            assert!(*keepalive <= before + ttl.mul_f64(*fraction) + Duration::from_millis(250));
+            previous = *keepalive;
+        }
+
+        assert!(entry.expires_at >= before + ttl);
+    }
+
+    #[test]
+    fn refresh_resets_the_keepalive_schedule() {
+        let mut entry = CacheEntry::new(Duration::from_secs(100));
+        entry.next_keepalive = KEEPALIVE_FRACTIONS.len();
+
+        entry.refresh(Duration::from_secs(50));
+
+        assert_eq!(entry.ttl, Duration::from_secs(50));
+        assert_eq!(entry.next_keepalive, 0);
+    }
+}
+
+/// Browse for instances of `service_names`, yielding a [`ServiceEvent`] every time one joins or
+/// leaves the network. Unlike [`one`]/[`multiple`] the returned stream never ends on its own;
+/// drop it to stop browsing.
+///
+/// Instances are cached by hostname: the first answer seen for an instance emits
+/// `ServiceEvent::Added`, later answers just refresh it, and it is removed (`ServiceEvent::
+/// Removed`) either when a PTR/SRV record arrives with TTL 0 (a goodbye packet) or when its TTL
+/// elapses without a refresh. To avoid that expiry, cached instances are re-queried at roughly
+/// 80%, 85%, 90% and 95% of their TTL, each with a little jitter so browsers don't all query in
+/// lockstep.
+pub fn browse(service_names: Vec<String>) -> Result<BrowseStream, Error> {
+    let responses = crate::discover::all(service_names.clone(), Duration::from_secs(10))?.listen();
+    let cache = Arc::new(Mutex::new(HashMap::<String, CacheEntry>::new()));
+    let (expired_tx, expired_rx) = async_std::channel::unbounded();
+
+    let keepalive = async_std::task::spawn(keepalive_task(service_names, cache.clone(), expired_tx));
+
+    let added_or_removed = responses.filter_map(move |result| {
+        let cache = cache.clone();
+
+        async move {
+            match result {
+                Ok(response) => on_response(response, &cache).await.map(Ok),
+                Err(e) => Some(Err(e)),
+            }
+        }
+    });
+
+    let expired = expired_rx.map(|name| Ok(ServiceEvent::Removed(name)));
+
+    Ok(BrowseStream {
+        inner: Box::pin(futures_util::stream::select(added_or_removed, expired)),
+        keepalive: Some(keepalive),
+    })
+}
+
+/// The stream returned by [`browse`]. Dropping it stops browsing: both the discovery task that
+/// re-sends the query (owned by the boxed `discover::DiscoveryStream` in `inner`) and the
+/// keepalive task that re-queries cached instances near TTL expiry are cancelled along with it.
+pub struct BrowseStream {
+    inner: Pin<Box<dyn Stream<Item = Result<ServiceEvent, Error>> + Send>>,
+    keepalive: Option<JoinHandle<()>>,
+}
+
+impl Stream for BrowseStream {
+    type Item = Result<ServiceEvent, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+impl Drop for BrowseStream {
+    fn drop(&mut self) {
+        // `JoinHandle` doesn't cancel its task on drop, only `.cancel().await` does — and
+        // `Drop::drop` can't await. Hand the cancellation off to its own detached task instead,
+        // so `keepalive_task` doesn't keep re-querying the network after callers drop the
+        // stream.
+        if let Some(task) = self.keepalive.take() {
+            async_std::task::spawn(task.cancel());
+        }
+    }
+}
+
+async fn on_response(
+    response: Response,
+    cache: &Arc<Mutex<HashMap<String, CacheEntry>>>,
+) -> Option<ServiceEvent> {
+    let name = response.hostname()?.to_owned();
+    let ttl = instance_ttl(&response, &name)?;
+
+    match apply_ttl(&mut *cache.lock().await, &name, ttl) {
+        Some(CacheTransition::Added) => Some(ServiceEvent::Added(response)),
+        Some(CacheTransition::Removed) => Some(ServiceEvent::Removed(name)),
+        None => None,
+    }
+}
+
+/// The TTL of the PTR/SRV record identifying `name`, used both to expire stale entries and to
+/// detect RFC 6762 goodbye packets (TTL == 0).
+///
+/// Matched by owner name rather than just "the first PTR/SRV record in the packet": a PTR
+/// record's owner is the service type, so it's matched by its target (the instance name); an
+/// SRV record's owner is the instance name itself. Without this, a response packet carrying
+/// records for more than one instance could attribute the wrong record's TTL to `name`.
+fn instance_ttl(response: &Response, name: &str) -> Option<Duration> {
+    response
+        .records()
+        .find(|record| match &record.kind {
+            RecordKind::PTR(target) => target == name,
+            RecordKind::SRV { .. } => record.name == name,
+            _ => false,
+        })
+        .map(|record| Duration::from_secs(u64::from(record.ttl)))
+}
+
+/// The event a cache update produces, if any — a refresh of an already-cached instance produces
+/// neither.
+#[derive(Debug, PartialEq, Eq)]
+enum CacheTransition {
+    Added,
+    Removed,
+}
+
+/// Applies a freshly-seen TTL for `name` to the cache, inserting/refreshing/removing its entry
+/// as appropriate. Kept free of async and of [`Response`] so the "exactly one outstanding Added
+/// before any Removed" invariant can be tested directly, without a runtime or a real response.
+fn apply_ttl(
+    cache: &mut HashMap<String, CacheEntry>,
+    name: &str,
+    ttl: Duration,
+) -> Option<CacheTransition> {
+    if ttl.is_zero() {
+        return cache.remove(name).map(|_| CacheTransition::Removed);
+    }
+
+    match cache.entry(name.to_owned()) {
+        Entry::Occupied(mut entry) => {
+            entry.get_mut().refresh(ttl);
+            None
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(CacheEntry::new(ttl));
+            Some(CacheTransition::Added)
+        }
+    }
+}
+
+#[cfg(test)]
+mod cache_transition_tests {
+    use super::*;
+
+    #[test]
+    fn first_sighting_emits_added() {
+        let mut cache = HashMap::new();
+        assert_eq!(
+            apply_ttl(&mut cache, "device", Duration::from_secs(120)),
+            Some(CacheTransition::Added)
+        );
+        assert!(cache.contains_key("device"));
+    }
+
+    #[test]
+    fn a_refresh_of_a_live_instance_emits_nothing() {
+        let mut cache = HashMap::new();
+        apply_ttl(&mut cache, "device", Duration::from_secs(120));
+
+        assert_eq!(apply_ttl(&mut cache, "device", Duration::from_secs(120)), None);
+        assert!(cache.contains_key("device"));
+    }
+
+    #[test]
+    fn a_goodbye_for_a_cached_instance_emits_removed() {
+        let mut cache = HashMap::new();
+        apply_ttl(&mut cache, "device", Duration::from_secs(120));
+
+        assert_eq!(
+            apply_ttl(&mut cache, "device", Duration::from_secs(0)),
+            Some(CacheTransition::Removed)
+        );
+        assert!(!cache.contains_key("device"));
+    }
+
+    #[test]
+    fn a_goodbye_for_an_unknown_instance_emits_nothing() {
+        let mut cache = HashMap::new();
+        assert_eq!(apply_ttl(&mut cache, "device", Duration::from_secs(0)), None);
+    }
+
+    #[test]
+    fn exactly_one_outstanding_added_before_any_removed() {
+        let mut cache = HashMap::new();
+        let mut added = 0;
+        let mut removed = 0;
+
+        // Join, refresh a few times, leave, then join again: Added must fire exactly once
+        // before the matching Removed, and refreshes in between must stay silent.
+        for ttl in [120, 120, 120, 0, 120] {
+            match apply_ttl(&mut cache, "device", Duration::from_secs(ttl)) {
+                Some(CacheTransition::Added) => added += 1,
+                Some(CacheTransition::Removed) => removed += 1,
+                None => {}
+            }
+            assert!(added - removed <= 1, "more than one outstanding Added for an instance");
+        }
+
+        assert_eq!((added, removed), (2, 1));
+    }
+}
+
+async fn keepalive_task(
+    service_names: Vec<String>,
+    cache: Arc<Mutex<HashMap<String, CacheEntry>>>,
+    expired_tx: async_std::channel::Sender<String>,
+) {
+    loop {
+        async_std::task::sleep(KEEPALIVE_TICK).await;
+
+        let now = Instant::now();
+        let mut needs_requery = false;
+        let mut expired = Vec::new();
+
+        {
+            let mut cache = cache.lock().await;
+
+            cache.retain(|name, entry| {
+                if now >= entry.expires_at {
+                    expired.push(name.clone());
+                    return false;
+                }
+
+                while entry.next_keepalive < entry.keepalives.len()
+                    && now >= entry.keepalives[entry.next_keepalive]
+                {
+                    entry.next_keepalive += 1;
+                    needs_requery = true;
+                }
+
+                true
+            });
+        }
+
+        for name in expired {
+            let _ = expired_tx.send(name).await;
+        }
+
+        if needs_requery {
+            if let Err(e) = crate::discover::requery(&service_names).await {
+                log::warn!("mdns: failed to send keepalive query: {}", e);
+            }
+        }
+    }
+}
+
+/// Which address families [`socket_addrs`]/[`resolve_service`] return, and in what order.
+/// Mirrors trust-dns's `LookupIpStrategy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Only return IPv4 addresses.
+    Ipv4Only,
+    /// Only return IPv6 addresses.
+    Ipv6Only,
+    /// Return both, IPv4 first.
+    PreferV4,
+    /// Return both, IPv6 first.
+    PreferV6,
+}
+
+/// A service instance resolved down to something immediately usable: addresses ready for
+/// `TcpStream::connect`, and its TXT metadata parsed into key/value pairs.
+#[derive(Clone, Debug)]
+pub struct ResolvedService {
+    /// The instance's SRV target combined with its A/AAAA records, ordered per the requested
+    /// [`IpPreference`].
+    pub addrs: Vec<SocketAddr>,
+    /// The instance's TXT record, parsed as `key=value` pairs. A key present without a value
+    /// (no `=`) maps to `None`, per RFC 6763 §6.4.
+    pub txt: HashMap<String, Option<String>>,
+}
+
+/// Resolve a single device by hostname and combine its SRV/A/AAAA/TXT records into a
+/// [`ResolvedService`], so callers don't have to walk [`Response`]'s raw record set themselves.
+pub async fn resolve_service<S>(
+    service_names: Vec<String>,
+    host_name: S,
+    timeout: Duration,
+    preference: IpPreference,
+) -> Result<Option<ResolvedService>, Error>
+where
+    S: AsRef<str>,
+{
+    let response = one(service_names, host_name, timeout).await?;
+    Ok(response.as_ref().map(|response| to_resolved_service(response, preference)))
+}
+
+/// Resolve a single device by hostname and return ready-to-connect [`SocketAddr`]s, combining
+/// its SRV record's target/port with the matching A/AAAA records.
+pub async fn socket_addrs<S>(
+    service_names: Vec<String>,
+    host_name: S,
+    timeout: Duration,
+    preference: IpPreference,
+) -> Result<Vec<SocketAddr>, Error>
+where
+    S: AsRef<str>,
+{
+    Ok(resolve_service(service_names, host_name, timeout, preference)
+        .await?
+        .map(|service| service.addrs)
+        .unwrap_or_default())
+}
+
+fn to_resolved_service(response: &Response, preference: IpPreference) -> ResolvedService {
+    let srv = response.records().find_map(|record| match &record.kind {
+        RecordKind::SRV { port, target, .. } => Some((*port, target.clone())),
+        _ => None,
+    });
+
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+
+    if let Some((port, target)) = &srv {
+        for record in response.records() {
+            match &record.kind {
+                RecordKind::A(addr) if &record.name == target => {
+                    v4.push(SocketAddr::from((*addr, *port)))
+                }
+                RecordKind::AAAA(addr) if &record.name == target => {
+                    v6.push(SocketAddr::from((*addr, *port)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let addrs = order_by_preference(v4, v6, preference);
+
+    let txt = response
+        .records()
+        .find_map(|record| match &record.kind {
+            RecordKind::TXT(entries) => Some(parse_txt(entries)),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    ResolvedService { addrs, txt }
+}
+
+/// Orders a service's IPv4/IPv6 addresses per the requested [`IpPreference`].
+fn order_by_preference(
+    v4: Vec<SocketAddr>,
+    v6: Vec<SocketAddr>,
+    preference: IpPreference,
+) -> Vec<SocketAddr> {
+    match preference {
+        IpPreference::Ipv4Only => v4,
+        IpPreference::Ipv6Only => v6,
+        IpPreference::PreferV4 => v4.into_iter().chain(v6).collect(),
+        IpPreference::PreferV6 => v6.into_iter().chain(v4).collect(),
+    }
+}
+
+fn parse_txt(entries: &[String]) -> HashMap<String, Option<String>> {
+    entries
+        .iter()
+        .map(|entry| match entry.split_once('=') {
+            Some((key, value)) => (key.to_owned(), Some(value.to_owned())),
+            None => (entry.clone(), None),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod resolved_service_tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv6Addr};
+
+    fn addr(ip: &str, port: u16) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), port)
+    }
+
+    fn v4_and_v6() -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        (
+            vec![addr("192.168.1.10", 8080)],
+            vec![SocketAddr::new(IpAddr::from(Ipv6Addr::LOCALHOST), 8080)],
+        )
+    }
+
+    #[test]
+    fn ipv4_only_drops_ipv6_addresses() {
+        let (v4, v6) = v4_and_v6();
+        assert_eq!(order_by_preference(v4.clone(), v6, IpPreference::Ipv4Only), v4);
+    }
+
+    #[test]
+    fn ipv6_only_drops_ipv4_addresses() {
+        let (v4, v6) = v4_and_v6();
+        assert_eq!(order_by_preference(v4, v6.clone(), IpPreference::Ipv6Only), v6);
+    }
+
+    #[test]
+    fn prefer_v4_orders_ipv4_first() {
+        let (v4, v6) = v4_and_v6();
+        let ordered = order_by_preference(v4.clone(), v6.clone(), IpPreference::PreferV4);
+        assert_eq!(ordered, [v4, v6].concat());
+    }
+
+    #[test]
+    fn prefer_v6_orders_ipv6_first() {
+        let (v4, v6) = v4_and_v6();
+        let ordered = order_by_preference(v4.clone(), v6.clone(), IpPreference::PreferV6);
+        assert_eq!(ordered, [v6, v4].concat());
+    }
+
+    #[test]
+    fn parse_txt_splits_key_value_pairs() {
+        let entries = vec!["path=/".to_owned(), "secure".to_owned(), "version=2".to_owned()];
+        let parsed = parse_txt(&entries);
+
+        assert_eq!(parsed.get("path"), Some(&Some("/".to_owned())));
+        assert_eq!(parsed.get("secure"), Some(&None));
+        assert_eq!(parsed.get("version"), Some(&Some("2".to_owned())));
+    }
+
+    #[test]
+    fn parse_txt_handles_value_containing_equals() {
+        let entries = vec!["query=a=b".to_owned()];
+        assert_eq!(parse_txt(&entries).get("query"), Some(&Some("a=b".to_owned())));
+    }
+}
@@ -0,0 +1,459 @@
+//! An mDNS responder: publishes local services and answers queries for them.
+//!
+//! This is the write side of the crate: where [`crate::discover`]/[`crate::resolve`] only send
+//! queries and listen for answers, [`Responder`] binds the multicast group, parses incoming
+//! queries, and answers the ones asking about services the caller has registered — modeled on
+//! Mozilla's `mdns_service`.
+
+use crate::discover::{MULTICAST_ADDR, MULTICAST_PORT};
+use crate::Error;
+use async_std::net::UdpSocket;
+use async_std::sync::RwLock;
+use async_std::task::{self, JoinHandle};
+use dns_parser::{Packet, QueryClass, QueryType};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Whether a [`Responder`] probes for name conflicts before announcing a service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Probe the network before announcing, and pick a new instance name (`"name (2)"`, `"name
+    /// (3)"`, ...) if another responder already owns it.
+    Probe,
+    /// Assume the caller already guarantees a unique name (e.g. by deriving it from a UUID, as
+    /// Mozilla's `mdns_service` does) and skip probing entirely.
+    AssumeUnique,
+}
+
+/// A service to publish: who it is, where it lives, and what metadata it advertises.
+#[derive(Clone, Debug)]
+pub struct ServiceRegistration {
+    /// The service instance's friendly name, e.g. `"Kitchen Printer"`.
+    pub instance_name: String,
+    /// The service type being advertised, e.g. `"_http._tcp.local"`.
+    pub service_type: String,
+    /// The TCP/UDP port the service listens on.
+    pub port: u16,
+    /// The hostname the SRV record points at, e.g. `"my-device.local"`.
+    pub target_host: String,
+    /// The addresses `target_host` resolves to.
+    pub addresses: Vec<IpAddr>,
+    /// Arbitrary key/value metadata published in the TXT record.
+    pub txt: HashMap<String, String>,
+}
+
+impl ServiceRegistration {
+    /// The fully-qualified instance name, e.g. `"Kitchen Printer._http._tcp.local"`.
+    fn instance_fqdn(&self) -> String {
+        format!("{}.{}", self.instance_name, self.service_type)
+    }
+}
+
+/// Default TTL used for published records, in seconds (RFC 6762 recommends 120s for most
+/// records).
+const DEFAULT_TTL: u32 = 120;
+
+struct Shared {
+    registrations: HashMap<String, ServiceRegistration>,
+}
+
+/// Answers mDNS queries for the services it's been told to publish.
+///
+/// Spawn one with [`Responder::spawn`], then call [`Responder::register_service`] /
+/// [`Responder::unregister_service`] as services come and go. Dropping the `Responder` stops the
+/// background task that answers queries.
+pub struct Responder {
+    shared: Arc<RwLock<Shared>>,
+    socket: UdpSocket,
+    conflict_policy: ConflictPolicy,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Responder {
+    /// Binds the mDNS multicast group and starts answering queries in the background.
+    pub async fn spawn(conflict_policy: ConflictPolicy) -> Result<Responder, Error> {
+        let socket = bind_multicast()?;
+        let shared = Arc::new(RwLock::new(Shared {
+            registrations: HashMap::new(),
+        }));
+
+        let task = task::spawn(answer_queries(socket.clone(), shared.clone()));
+
+        Ok(Responder {
+            shared,
+            socket,
+            conflict_policy,
+            task: Some(task),
+        })
+    }
+
+    /// Publishes `registration`, probing for name conflicts first if the responder was spawned
+    /// with [`ConflictPolicy::Probe`]. Returns the instance name actually announced, which may
+    /// differ from `registration.instance_name` if a conflict was found and resolved.
+    pub async fn register_service(&self, mut registration: ServiceRegistration) -> Result<String, Error> {
+        if self.conflict_policy == ConflictPolicy::Probe {
+            registration = self.resolve_name_conflict(registration).await?;
+        }
+
+        let fqdn = registration.instance_fqdn();
+        let announcement = build_announcement(&registration);
+
+        self.shared
+            .write()
+            .await
+            .registrations
+            .insert(fqdn.clone(), registration);
+
+        // RFC 6762 §8.3: announce twice, a second apart, to guard against the first packet
+        // being dropped.
+        self.socket
+            .send_to(&announcement, (MULTICAST_ADDR, MULTICAST_PORT))
+            .await?;
+        task::sleep(Duration::from_secs(1)).await;
+        self.socket
+            .send_to(&announcement, (MULTICAST_ADDR, MULTICAST_PORT))
+            .await?;
+
+        Ok(fqdn)
+    }
+
+    /// Stops publishing the instance named `instance_fqdn` (as returned by
+    /// [`Responder::register_service`]), sending a goodbye packet (TTL 0) so other hosts drop it
+    /// from their caches immediately instead of waiting for it to expire.
+    pub async fn unregister_service(&self, instance_fqdn: &str) -> Result<(), Error> {
+        let registration = self.shared.write().await.registrations.remove(instance_fqdn);
+
+        if let Some(registration) = registration {
+            let goodbye = build_announcement_with_ttl(&registration, 0);
+            self.socket
+                .send_to(&goodbye, (MULTICAST_ADDR, MULTICAST_PORT))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Probes the network for `registration.instance_fqdn()` and, on conflict, appends " (2)",
+    /// " (3)", ... until a free name is found.
+    async fn resolve_name_conflict(
+        &self,
+        mut registration: ServiceRegistration,
+    ) -> Result<ServiceRegistration, Error> {
+        let base_name = registration.instance_name.clone();
+
+        for attempt in 2.. {
+            if !self.name_is_taken(&registration.instance_fqdn()).await? {
+                return Ok(registration);
+            }
+
+            registration.instance_name = format!("{} ({})", base_name, attempt);
+        }
+
+        unreachable!("the naming loop above never terminates on its own")
+    }
+
+    async fn name_is_taken(&self, fqdn: &str) -> Result<bool, Error> {
+        // Use our own socket rather than `self.socket`: that one is being read in a tight loop
+        // by the background `answer_queries` task, and UDP only delivers each datagram to one
+        // reader, so sharing it would race that task for the conflicting peer's reply. A second
+        // socket bound with SO_REUSEPORT (see `bind_multicast`) gets its own copy of every
+        // multicast datagram instead.
+        let probe_socket = bind_multicast()?;
+
+        let query = build_query(fqdn);
+        probe_socket
+            .send_to(&query, (MULTICAST_ADDR, MULTICAST_PORT))
+            .await?;
+
+        let mut buffer = [0u8; 4096];
+        let probe = async {
+            loop {
+                let (count, _) = probe_socket.recv_from(&mut buffer).await?;
+                if let Ok(packet) = Packet::parse(&buffer[..count]) {
+                    if packet.answers.iter().any(|a| a.name.to_string() == fqdn) {
+                        return Ok::<_, Error>(true);
+                    }
+                }
+            }
+        };
+
+        match async_std::future::timeout(Duration::from_millis(250), probe).await {
+            Ok(result) => result,
+            Err(_) => Ok(false),
+        }
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        // `JoinHandle` doesn't cancel its task on drop, only `.cancel().await` does — and
+        // `Drop::drop` can't await. Hand the cancellation off to its own detached task instead,
+        // so `answer_queries` (and the socket it holds) doesn't outlive the `Responder`.
+        if let Some(task) = self.task.take() {
+            task::spawn(task.cancel());
+        }
+    }
+}
+
+fn bind_multicast() -> Result<UdpSocket, Error> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MULTICAST_PORT)).into())?;
+    socket.join_multicast_v4(&MULTICAST_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+
+    Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+}
+
+async fn answer_queries(socket: UdpSocket, shared: Arc<RwLock<Shared>>) {
+    let mut buffer = [0u8; 4096];
+
+    loop {
+        let (count, from) = match socket.recv_from(&mut buffer).await {
+            Ok(result) => result,
+            Err(e) => {
+                log::warn!("mdns: responder failed to read a query: {}", e);
+                continue;
+            }
+        };
+
+        let packet = match Packet::parse(&buffer[..count]) {
+            Ok(packet) => packet,
+            Err(_) => continue,
+        };
+
+        if packet.header.query {
+            if let Err(e) = respond(&socket, &shared, &packet, from).await {
+                log::warn!("mdns: responder failed to answer a query: {}", e);
+            }
+        }
+    }
+}
+
+async fn respond(
+    socket: &UdpSocket,
+    shared: &Arc<RwLock<Shared>>,
+    packet: &Packet<'_>,
+    from: SocketAddr,
+) -> Result<(), Error> {
+    let registrations = shared.read().await;
+
+    for question in &packet.questions {
+        let name = question.qname.to_string();
+
+        let matching = registrations.registrations.values().find(|registration| {
+            matches!(question.qtype, QueryType::PTR) && name == registration.service_type
+                || name == registration.instance_fqdn()
+                || name == registration.target_host
+        });
+
+        if let Some(registration) = matching {
+            let answer = build_answer(registration, question.qtype);
+            let qu = question.qu;
+            let dest = if qu { from } else { SocketAddr::from((MULTICAST_ADDR, MULTICAST_PORT)) };
+            socket.send_to(&answer, dest).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// --- minimal DNS wire-format answer building -------------------------------------------------
+//
+// dns_parser only builds questions, so responses are assembled by hand. Compression isn't
+// implemented (every name is written out in full); that costs a few extra bytes per packet but
+// keeps this straightforward.
+
+fn build_query(fqdn: &str) -> Vec<u8> {
+    let mut builder = dns_parser::Builder::new_query(0, false);
+    builder.add_question(fqdn, false, QueryType::ANY, QueryClass::IN);
+    builder.build().unwrap_or_default()
+}
+
+fn build_announcement(registration: &ServiceRegistration) -> Vec<u8> {
+    build_answer(registration, QueryType::ANY)
+}
+
+fn build_announcement_with_ttl(registration: &ServiceRegistration, ttl: u32) -> Vec<u8> {
+    build_answer_with_ttl(registration, QueryType::ANY, ttl)
+}
+
+fn build_answer(registration: &ServiceRegistration, qtype: QueryType) -> Vec<u8> {
+    build_answer_with_ttl(registration, qtype, DEFAULT_TTL)
+}
+
+fn build_answer_with_ttl(registration: &ServiceRegistration, qtype: QueryType, ttl: u32) -> Vec<u8> {
+    let mut packet = Vec::new();
+
+    // Header: one response, authoritative, no questions, answer count filled in below.
+    packet.extend_from_slice(&[
+        0x00, 0x00, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]);
+    let mut answer_count: u16 = 0;
+
+    if matches!(qtype, QueryType::PTR | QueryType::ANY) {
+        write_record(&mut packet, &registration.service_type, 12, ttl, |buf| {
+            write_name(buf, &registration.instance_fqdn());
+        });
+        answer_count += 1;
+    }
+
+    if matches!(qtype, QueryType::SRV | QueryType::ANY) {
+        write_record(&mut packet, &registration.instance_fqdn(), 33, ttl, |buf| {
+            buf.extend_from_slice(&0u16.to_be_bytes()); // priority
+            buf.extend_from_slice(&0u16.to_be_bytes()); // weight
+            buf.extend_from_slice(&registration.port.to_be_bytes());
+            write_name(buf, &registration.target_host);
+        });
+        answer_count += 1;
+    }
+
+    if matches!(qtype, QueryType::TXT | QueryType::ANY) {
+        write_record(&mut packet, &registration.instance_fqdn(), 16, ttl, |buf| {
+            for (key, value) in &registration.txt {
+                let entry = format!("{}={}", key, value);
+                buf.push(entry.len() as u8);
+                buf.extend_from_slice(entry.as_bytes());
+            }
+        });
+        answer_count += 1;
+    }
+
+    for address in &registration.addresses {
+        match (qtype, address) {
+            (QueryType::A, IpAddr::V4(_)) | (QueryType::ANY, IpAddr::V4(_)) => {}
+            (QueryType::AAAA, IpAddr::V6(_)) | (QueryType::ANY, IpAddr::V6(_)) => {}
+            _ => continue,
+        }
+
+        let rtype = if address.is_ipv4() { 1 } else { 28 };
+        write_record(&mut packet, &registration.target_host, rtype, ttl, |buf| match address {
+            IpAddr::V4(addr) => buf.extend_from_slice(&addr.octets()),
+            IpAddr::V6(addr) => buf.extend_from_slice(&addr.octets()),
+        });
+        answer_count += 1;
+    }
+
+    packet[6..8].copy_from_slice(&answer_count.to_be_bytes());
+    packet
+}
+
+fn write_record(
+    packet: &mut Vec<u8>,
+    name: &str,
+    rtype: u16,
+    ttl: u32,
+    write_rdata: impl FnOnce(&mut Vec<u8>),
+) {
+    write_name(packet, name);
+    packet.extend_from_slice(&rtype.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes()); // class IN
+    packet.extend_from_slice(&ttl.to_be_bytes());
+
+    let rdata_start = packet.len() + 2;
+    packet.extend_from_slice(&0u16.to_be_bytes()); // rdlength placeholder
+    write_rdata(packet);
+    let rdlength = (packet.len() - rdata_start) as u16;
+    packet[rdata_start - 2..rdata_start].copy_from_slice(&rdlength.to_be_bytes());
+}
+
+fn write_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+        if label.is_empty() {
+            continue;
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_name_encodes_length_prefixed_labels_and_drops_the_trailing_dot() {
+        let mut packet = Vec::new();
+        write_name(&mut packet, "my-device.local.");
+
+        assert_eq!(
+            packet,
+            [
+                vec![9u8],
+                b"my-device".to_vec(),
+                vec![5u8],
+                b"local".to_vec(),
+                vec![0u8],
+            ]
+            .concat()
+        );
+    }
+
+    fn registration() -> ServiceRegistration {
+        ServiceRegistration {
+            instance_name: "Kitchen Printer".to_owned(),
+            service_type: "_http._tcp.local".to_owned(),
+            port: 8080,
+            target_host: "my-device.local".to_owned(),
+            addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10))],
+            txt: HashMap::from([("path".to_owned(), "/".to_owned())]),
+        }
+    }
+
+    /// Reads back just enough of the header to check the answer count this module's own
+    /// `build_answer_with_ttl` wrote — it's the only part of the header tests need, so there's
+    /// no need for a full parser here.
+    fn answer_count(packet: &[u8]) -> u16 {
+        u16::from_be_bytes([packet[6], packet[7]])
+    }
+
+    #[test]
+    fn build_answer_with_ttl_includes_one_record_per_answered_type() {
+        let registration = registration();
+
+        let ptr_only = build_answer_with_ttl(&registration, QueryType::PTR, DEFAULT_TTL);
+        assert_eq!(answer_count(&ptr_only), 1);
+
+        let srv_only = build_answer_with_ttl(&registration, QueryType::SRV, DEFAULT_TTL);
+        assert_eq!(answer_count(&srv_only), 1);
+
+        // ANY answers PTR, SRV, TXT and one A record for the single registered address.
+        let everything = build_answer_with_ttl(&registration, QueryType::ANY, DEFAULT_TTL);
+        assert_eq!(answer_count(&everything), 4);
+    }
+
+    #[test]
+    fn build_announcement_with_ttl_writes_the_requested_ttl_into_every_record() {
+        let registration = registration();
+        let goodbye = build_announcement_with_ttl(&registration, 0);
+
+        // Walk each record's fixed-size TTL field. Record layout here is: name (terminated by a
+        // 0x00 label), type (2 bytes), class (2 bytes), ttl (4 bytes), rdlength (2 bytes), rdata.
+        let mut offset = 12; // past the 12-byte header
+        let mut seen = 0;
+
+        while offset < goodbye.len() {
+            while goodbye[offset] != 0 {
+                offset += 1 + goodbye[offset] as usize;
+            }
+            offset += 1; // the terminating 0x00 label
+
+            let ttl = u32::from_be_bytes(goodbye[offset + 4..offset + 8].try_into().unwrap());
+            assert_eq!(ttl, 0);
+
+            let rdlength = u16::from_be_bytes([goodbye[offset + 8], goodbye[offset + 9]]) as usize;
+            offset += 10 + rdlength;
+            seen += 1;
+        }
+
+        assert_eq!(seen, answer_count(&goodbye) as usize);
+    }
+}
+